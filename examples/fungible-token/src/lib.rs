@@ -8,6 +8,7 @@ use fvm_ipld_encoding::RawBytes;
 use fvm_shared::address::Address;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ExitCode;
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::MethodNum;
 use ipc_atomic_execution::Method::{PreCommit, Revoke};
 use ipc_atomic_execution::{AtomicExecID, AtomicInputID, PreCommitParams, RevokeParams};
@@ -21,6 +22,12 @@ fvm_actors_runtime::wasm_trampoline!(Actor);
 
 struct Actor;
 
+/// Number of epochs, counted from `InitAtomicTransfer`, after which an
+/// atomic transfer that has not been committed is considered stale:
+/// anyone may then cancel it, a late commit rolls it back instead, and
+/// the coordinator may sweep (roll back) the execution.
+const ATOMIC_TRANSFER_TIMEOUT_EPOCHS: ChainEpoch = 120;
+
 #[derive(Clone, Serialize_tuple, Deserialize_tuple)]
 pub struct ConstructorParams {
     pub ipc_gateway: Address,
@@ -197,12 +204,14 @@ impl Actor {
             || actor_error!(illegal_argument; "cannot resolve destination account address"),
         )?;
 
+        let timeout_epoch = rt.curr_epoch() + ATOMIC_TRANSFER_TIMEOUT_EPOCHS;
         let input_id = rt.transaction(|st: &mut State, rt| {
             st.init_atomic_transfer(
                 rt.store(),
                 from_id.id().unwrap(),
                 to_id.id().unwrap(),
                 amount,
+                timeout_epoch,
             )
             .map_err(|e| {
                 e.downcast_default(ExitCode::USR_UNSPECIFIED, "cannot init atomic transfer")
@@ -219,8 +228,10 @@ impl Actor {
         BS: Blockstore + Clone,
         RT: Runtime<BS>,
     {
+        let caller = rt.message().caller();
+        let curr_epoch = rt.curr_epoch();
         rt.transaction(|st: &mut State, rt| {
-            st.cancel_atomic_transfer(rt.store(), input_id)
+            st.cancel_atomic_transfer(rt.store(), input_id, caller.id().unwrap(), curr_epoch)
                 .map_err(|e| {
                     e.downcast_default(ExitCode::USR_UNSPECIFIED, "cannot cancel atomic transfer")
                 })
@@ -238,13 +249,13 @@ impl Actor {
         let PrepareAtomicParams { input_ids } = params;
 
         let st: State = rt.state()?;
-        let exec_id = rt.transaction(|st: &mut State, rt| {
-            let exec_id = st
+        let (exec_id, timeout_epoch) = rt.transaction(|st: &mut State, rt| {
+            let (exec_id, timeout_epoch) = st
                 .prep_atomic_transfer(rt.store(), &input_ids)
                 .map_err(|e| {
                     e.downcast_default(ExitCode::USR_UNSPECIFIED, "cannot prepare atomic transfer")
                 })?;
-            Ok(exec_id)
+            Ok((exec_id, timeout_epoch))
         })?;
 
         let msg = CrossMsg {
@@ -255,6 +266,8 @@ impl Actor {
                     actors: input_ids.keys().cloned().collect(),
                     exec_id: exec_id.clone(),
                     commit: method_hash!("CommitAtomicTransfer"), // requires literal string
+                    rollback: method_hash!("RollbackAtomicTransfer"), // requires literal string
+                    timeout_epoch: Some(timeout_epoch),
                 })?,
                 ..Default::default()
             },
@@ -294,10 +307,12 @@ impl Actor {
             actor_error!(forbidden; "unexpected cross-net message origin");
         }
 
+        let curr_epoch = rt.curr_epoch();
         rt.transaction(|st: &mut State, rt| {
-            st.commit_atomic_transfer(rt.store(), exec_id).map_err(|e| {
-                e.downcast_default(ExitCode::USR_UNSPECIFIED, "cannot commit atomic transfer")
-            })
+            st.commit_atomic_transfer(rt.store(), exec_id, curr_epoch)
+                .map_err(|e| {
+                    e.downcast_default(ExitCode::USR_UNSPECIFIED, "cannot commit atomic transfer")
+                })
         })
     }
 
@@ -4,9 +4,14 @@ use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::{Cbor, RawBytes};
 use fvm_ipld_hamt::BytesKey;
 use fvm_primitives::{TCid, THamt};
-use fvm_shared::{address::Address, bigint::Zero, econ::TokenAmount, ActorID, HAMT_BIT_WIDTH};
+use fvm_shared::{
+    address::Address, bigint::Zero, clock::ChainEpoch, econ::TokenAmount, ActorID,
+    HAMT_BIT_WIDTH,
+};
 use integer_encoding::VarInt;
-use ipc_atomic_execution::{AtomicExecID, AtomicExecRegistry, AtomicInputID, AtomicInputState};
+use ipc_atomic_execution::{
+    AtomicExecID, AtomicExecRegistry, AtomicInputID, AtomicInputState, LockMode,
+};
 use ipc_gateway::IPCAddress;
 use serde::{Deserialize, Serialize};
 use serde_tuple::{Deserialize_tuple, Serialize_tuple};
@@ -128,6 +133,7 @@ impl State {
         from: ActorID,
         to: ActorID,
         amount: TokenAmount,
+        timeout_epoch: ChainEpoch,
     ) -> anyhow::Result<AtomicInputID> {
         let from_key = Self::account_key(from);
         let mut balances = self.balances.load(bs)?;
@@ -140,8 +146,13 @@ impl State {
         let input_id = self.atomic_registry.init_atomic_exec(
             bs,
             std::iter::once(&mut from_state),
-            RawBytes::serialize(AtomicTransfer { from, to, amount })?,
-            true,
+            RawBytes::serialize(AtomicTransfer {
+                from,
+                to,
+                amount,
+                timeout_epoch,
+            })?,
+            LockMode::Exclusive,
         )?;
 
         balances.set(from_key, from_state)?;
@@ -155,12 +166,19 @@ impl State {
         &mut self,
         bs: &impl Blockstore,
         input_id: AtomicInputID,
+        caller: ActorID,
+        curr_epoch: ChainEpoch,
     ) -> anyhow::Result<()> {
         let atomic_registry = &mut self.atomic_registry;
         let input = atomic_registry
             .atomic_input(bs, &input_id)?
             .ok_or_else(|| anyhow::anyhow!("unexpected own input ID"))?;
-        let AtomicTransfer { from, .. } = input.deserialize()?;
+        let AtomicTransfer {
+            from, timeout_epoch, ..
+        } = input.deserialize()?;
+        if caller != from && curr_epoch <= timeout_epoch {
+            anyhow::bail!("only the sender may cancel an atomic transfer before it times out");
+        }
         let from_key = Self::account_key(from);
         let mut balances = self.balances.load(bs)?;
         let mut from_state = balances.get(&from_key)?.cloned().unwrap_or_default();
@@ -174,7 +192,7 @@ impl State {
         &mut self,
         bs: &impl Blockstore,
         input_ids: &HashMap<IPCAddress, AtomicInputID>,
-    ) -> anyhow::Result<AtomicExecID> {
+    ) -> anyhow::Result<(AtomicExecID, ChainEpoch)> {
         let own_input_id = input_ids
             .get(self.ipc_address())
             .ok_or_else(|| anyhow::anyhow!("missing own input ID"))?;
@@ -182,7 +200,9 @@ impl State {
         let input = atomic_registry
             .atomic_input(bs, &own_input_id)?
             .ok_or_else(|| anyhow::anyhow!("unexpected own input ID"))?;
-        let AtomicTransfer { from, .. } = input.deserialize()?;
+        let AtomicTransfer {
+            from, timeout_epoch, ..
+        } = input.deserialize()?;
         let from_key = Self::account_key(from);
         let mut balances = self.balances.load(bs)?;
         let mut from_state = balances.get(&from_key)?.cloned().unwrap_or_default();
@@ -197,23 +217,37 @@ impl State {
         balances.set(from_key, from_state)?;
         self.balances.flush(balances)?;
 
-        Ok(exec_id)
+        Ok((exec_id, timeout_epoch))
     }
 
     pub fn commit_atomic_transfer(
         &mut self,
         bs: &impl Blockstore,
         exec_id: AtomicExecID,
+        curr_epoch: ChainEpoch,
     ) -> anyhow::Result<()> {
-        let atomic_registry = &mut self.atomic_registry;
-        let output = atomic_registry
+        let output = self
+            .atomic_registry
             .atomic_output(bs, &exec_id)?
             .ok_or_else(|| anyhow::anyhow!("unexpected exec ID"))?;
-        let AtomicTransfer { from, to, amount } = output.deserialize()?;
+        let AtomicTransfer {
+            from,
+            to,
+            amount,
+            timeout_epoch,
+        } = output.deserialize()?;
+
+        // A peer may still show up with a commit after we've already
+        // timed out; honor the timeout and roll back instead.
+        if curr_epoch > timeout_epoch {
+            return self.rollback_atomic_transfer(bs, exec_id);
+        }
+
         let from_key = Self::account_key(from);
         let mut balances = self.balances.load(bs)?;
         let mut from_state = balances.get(&from_key)?.cloned().unwrap_or_default();
-        atomic_registry.commit_atomic_exec(bs, exec_id, std::iter::once(&mut from_state))?;
+        self.atomic_registry
+            .commit_atomic_exec(bs, exec_id, std::iter::once(&mut from_state))?;
         balances.set(from_key, from_state)?;
         self.balances.flush(balances)?;
         self.transfer(bs, from, to, amount)?;
@@ -249,4 +283,5 @@ struct AtomicTransfer {
     from: ActorID,
     to: ActorID,
     amount: TokenAmount,
+    timeout_epoch: ChainEpoch,
 }
@@ -1,8 +1,8 @@
 use cid::multihash::{Blake2b256, MultihashDigest};
 use cid::multihash::{Code, Hasher};
+use crossbeam_utils::atomic::AtomicCell;
 use fvm_ipld_hamt::BytesKey;
 use ipc_gateway::IPCAddress;
-use std::cell::Cell;
 use std::collections::HashMap;
 use std::ops::Deref;
 
@@ -16,20 +16,110 @@ use serde::{self, de::DeserializeOwned, Serialize};
 use serde_tuple::Serialize_tuple;
 
 /// State that supports locking, as well as computing its CID.
+///
+/// Locking follows the usual `RwLock` discipline: any number of
+/// shared locks may be held concurrently, but an exclusive lock can
+/// never coexist with either a shared or another exclusive lock.
 pub trait LockableState: Cbor {
-    /// Locks the state so that it cannot be changed until unlocked.
-    fn lock(&mut self) -> anyhow::Result<()>;
+    /// Exclusively locks the state so that it cannot be read or
+    /// changed by another atomic execution until unlocked.
+    fn lock_exclusive(&mut self) -> anyhow::Result<()>;
+
+    /// Adds a shared (read-only) lock on the state, allowing other
+    /// atomic executions to hold a shared lock at the same time.
+    fn lock_shared(&mut self) -> anyhow::Result<()>;
 
-    /// Unlocks the state and allows it to be modified.
+    /// Unlocks an exclusive lock and allows the state to be modified
+    /// again.
     fn unlock(&mut self) -> anyhow::Result<()>;
 
-    /// Checks if the state is locked.
+    /// Releases one shared lock, unlocking the state once the last
+    /// shared lock is released.
+    fn unlock_shared(&mut self) -> anyhow::Result<()>;
+
+    /// Checks if the state is locked, either shared or exclusively.
     fn is_locked(&self) -> bool;
 
     /// Returns current state CID.
     fn cid(&self) -> Cid {
         cid_from_cbor(self)
     }
+
+    /// Alias for [`lock_exclusive`](Self::lock_exclusive).
+    fn lock(&mut self) -> anyhow::Result<()> {
+        self.lock_exclusive()
+    }
+}
+
+/// Lock mode requested by a caller of
+/// [`init_atomic_exec`](AtomicExecRegistry::init_atomic_exec) or
+/// [`prepare_atomic_exec`](AtomicExecRegistry::prepare_atomic_exec)
+/// for a piece of state that is not yet locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Do not lock the state; only capture its CID for later
+    /// verification.
+    Unlocked,
+    /// Add a shared (read-only) lock.
+    Shared,
+    /// Take an exclusive lock.
+    Exclusive,
+}
+
+/// Internal lock state of an [`AtomicInputState`].
+///
+/// Serialized the same way the previous `bool` field was for the
+/// `Unlocked`/`Exclusive` cases, so that existing on-chain state
+/// (which never held a shared lock) decodes unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockState {
+    Unlocked,
+    Shared(u32),
+    Exclusive,
+}
+
+impl LockState {
+    fn is_locked(&self) -> bool {
+        !matches!(self, LockState::Unlocked)
+    }
+}
+
+impl Default for LockState {
+    fn default() -> Self {
+        LockState::Unlocked
+    }
+}
+
+impl Serialize for LockState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            LockState::Unlocked => false.serialize(serializer),
+            LockState::Exclusive => true.serialize(serializer),
+            LockState::Shared(n) => n.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LockState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bool(bool),
+            Count(u32),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Bool(false) => LockState::Unlocked,
+            Repr::Bool(true) => LockState::Exclusive,
+            Repr::Count(n) => LockState::Shared(n),
+        })
+    }
 }
 
 /// Computes the CID of a CBOR object.
@@ -46,6 +136,13 @@ fn cid_from_cbor(obj: &impl Cbor) -> Cid {
 /// It can be either incorporated into other data structure, or
 /// referred to by its CID. In the latter case, it is user's
 /// responsibility to flush to and load from the blockstore.
+///
+/// `AtomicInputState<T>` is `Send + Sync` whenever `T` is, so it can
+/// be shared behind an `Arc` without external locking; the CID cache
+/// is a `crossbeam_utils` `AtomicCell` (which, for a value as large as
+/// a CID, synchronizes through crossbeam's internal locks rather than
+/// native atomics), and concurrent `cid()` calls simply race
+/// harmlessly to the same result.
 #[derive(Debug, Serialize_tuple, Deserialize_tuple)]
 pub struct AtomicInputState<T>
 where
@@ -53,10 +150,10 @@ where
 {
     // Cached CID value representing the current content.
     #[serde(skip)]
-    cid: Cell<Option<Cid>>,
+    cid: AtomicCell<Option<Cid>>,
 
-    // Flag indicating if the state is locked.
-    locked: bool,
+    // Current lock state.
+    lock: LockState,
 
     // Arbitrary piece of state.
     state: T,
@@ -67,8 +164,8 @@ impl<T: Serialize + DeserializeOwned> AtomicInputState<T> {
     /// Converts some state into a lockable piece of state.
     pub fn new(state: T) -> Self {
         Self {
-            cid: Cell::new(None),
-            locked: false,
+            cid: AtomicCell::new(None),
+            lock: LockState::Unlocked,
             state,
         }
     }
@@ -77,7 +174,7 @@ impl<T: Serialize + DeserializeOwned> AtomicInputState<T> {
     pub fn load(cid: &Cid, bs: &impl Blockstore) -> anyhow::Result<Option<Self>> {
         let res = bs.get_cbor::<Self>(cid)?;
         if let Some(s) = res.as_ref() {
-            s.cid.set(Some(*cid)); // cache known CID
+            s.cid.store(Some(*cid)); // cache known CID
         }
         Ok(res)
     }
@@ -85,19 +182,19 @@ impl<T: Serialize + DeserializeOwned> AtomicInputState<T> {
     /// Flushes the content to the blockstore.
     pub fn flush(&self, bs: &impl Blockstore) -> anyhow::Result<Cid> {
         let cid = bs.put_cbor(&self, Code::Blake2b256)?;
-        self.cid.set(Some(cid)); // cache computed CID
+        self.cid.store(Some(cid)); // cache computed CID
         Ok(cid)
     }
 
     /// Attempts to get a mutable reference to the inner content;
-    /// fails if the state is locked.
+    /// fails if the state is locked, shared or exclusive.
     pub fn get_mut(&mut self) -> anyhow::Result<&mut T> {
-        match self.locked {
-            false => {
-                self.cid.set(None); // invalidate cached CID
+        match self.lock {
+            LockState::Unlocked => {
+                self.cid.store(None); // invalidate cached CID
                 Ok(&mut self.state)
             }
-            true => Err(anyhow::anyhow!("cannot modify locked state")),
+            _ => Err(anyhow::anyhow!("cannot modify locked state")),
         }
     }
 
@@ -122,38 +219,70 @@ impl<T: Serialize + DeserializeOwned> Deref for AtomicInputState<T> {
 }
 
 impl<T: Serialize + DeserializeOwned> LockableState for AtomicInputState<T> {
-    fn lock(&mut self) -> anyhow::Result<()> {
-        match self.locked {
-            false => {
-                self.cid.set(None); // invalidate cached CID
-                self.locked = true;
+    fn lock_exclusive(&mut self) -> anyhow::Result<()> {
+        match self.lock {
+            LockState::Unlocked => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Exclusive;
                 Ok(())
             }
-            true => Err(anyhow::anyhow!("state already locked")),
+            _ => Err(anyhow::anyhow!("state already locked")),
+        }
+    }
+
+    fn lock_shared(&mut self) -> anyhow::Result<()> {
+        match self.lock {
+            LockState::Unlocked => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Shared(1);
+                Ok(())
+            }
+            LockState::Shared(n) => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Shared(n + 1);
+                Ok(())
+            }
+            LockState::Exclusive => Err(anyhow::anyhow!("state already exclusively locked")),
         }
     }
 
     fn unlock(&mut self) -> anyhow::Result<()> {
-        match self.locked {
-            true => {
-                self.cid.set(None); // invalidate cached CID
-                self.locked = false;
+        match self.lock {
+            LockState::Exclusive => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Unlocked;
                 Ok(())
             }
-            false => Err(anyhow::anyhow!("state not locked")),
+            _ => Err(anyhow::anyhow!("state not exclusively locked")),
+        }
+    }
+
+    fn unlock_shared(&mut self) -> anyhow::Result<()> {
+        match self.lock {
+            LockState::Shared(1) => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Unlocked;
+                Ok(())
+            }
+            LockState::Shared(n) if n > 1 => {
+                self.cid.store(None); // invalidate cached CID
+                self.lock = LockState::Shared(n - 1);
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("state not shared-locked")),
         }
     }
 
     fn is_locked(&self) -> bool {
-        self.locked
+        self.lock.is_locked()
     }
 
     fn cid(&self) -> Cid {
-        match self.cid.get() {
+        match self.cid.load() {
             Some(cid) => cid,
             None => {
                 let cid = cid_from_cbor(self);
-                self.cid.set(Some(cid)); // cache computed CID
+                self.cid.store(Some(cid)); // cache computed CID
                 cid
             }
         }
@@ -180,6 +309,42 @@ pub type AtomicExecID = RawBytes;
 
 type AtomicExecNonce = u64;
 
+/// Current protocol version implemented by this crate, used to
+/// derive input and execution IDs. Bump this whenever
+/// [`AtomicExecRegistry::new_input_id`] or
+/// [`AtomicExecRegistry::compute_exec_id`] change in a way that would
+/// make two actors disagree on the derived IDs.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Protocol version of on-chain state created before version
+/// negotiation existed. Kept so that already-deployed registries
+/// deserialize as this version and keep hashing the way they always
+/// did.
+pub const LEGACY_PROTOCOL_VERSION: u16 = 0;
+
+/// An atomic execution input ID together with the protocol version
+/// of the registry that produced it.
+///
+/// Participants exchange these (rather than bare
+/// [`AtomicInputID`]s) so that
+/// [`prepare_atomic_exec`](AtomicExecRegistry::prepare_atomic_exec)
+/// can detect a version mismatch before deriving an execution ID
+/// that the mismatched peer would never agree on.
+///
+/// This is a registry-internal mechanism: the `fungible-token`
+/// example bundled in this crate still exchanges bare
+/// [`AtomicInputID`]s in its own cross-message params and does not
+/// exercise version negotiation end to end. An actor wanting the
+/// negotiation to actually take effect needs to carry
+/// `AtomicInputIdent` (not just `AtomicInputID`) through its own
+/// equivalent of the example's `PrepareAtomicParams`/
+/// `AbortAtomicParams`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct AtomicInputIdent {
+    pub input_id: AtomicInputID,
+    pub protocol_version: u16,
+}
+
 /// Internal state associated with an atomic execution input.
 #[derive(Debug, PartialEq, Serialize_tuple, Deserialize_tuple)]
 struct AtomicInputEntry {
@@ -203,6 +368,11 @@ struct AtomicOutputEntry {
 /// blockstore.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AtomicExecRegistry {
+    // Absent in on-chain state predating version negotiation, which
+    // defaults to `LEGACY_PROTOCOL_VERSION` and keeps hashing the way
+    // it always did.
+    #[serde(default)]
+    protocol_version: u16,
     nonce: AtomicExecNonce,
     input_ids: TCid<THamt<AtomicInputID, AtomicInputEntry>>,
     exec_ids: TCid<THamt<AtomicExecID, AtomicOutputEntry>>,
@@ -216,12 +386,40 @@ impl AtomicExecRegistry {
     /// the registry itself is not flushed to the blockstore.
     pub fn new(bs: &impl Blockstore) -> anyhow::Result<AtomicExecRegistry> {
         Ok(Self {
+            protocol_version: PROTOCOL_VERSION,
             nonce: 0,
             input_ids: TCid::new_hamt(bs)?,
             exec_ids: TCid::new_hamt(bs)?,
         })
     }
 
+    /// Returns the protocol version this registry was constructed
+    /// with.
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version
+    }
+
+    /// Checks whether this registry supports a given protocol
+    /// feature, identified by the minimum protocol version that
+    /// introduced it.
+    pub fn supports(&self, feature_version: u16) -> bool {
+        self.protocol_version >= feature_version
+    }
+
+    /// Checks that `other_version` can interoperate with this
+    /// registry's protocol version, i.e. that the two would derive
+    /// matching input/execution IDs.
+    pub fn check_compatible(&self, other_version: u16) -> anyhow::Result<()> {
+        if self.protocol_version != other_version {
+            anyhow::bail!(
+                "incompatible atomic execution protocol version: expected {}, got {}",
+                self.protocol_version,
+                other_version
+            );
+        }
+        Ok(())
+    }
+
     /// Loads the atomic execution registry from the supplied
     /// blockstore by its CID.
     pub fn load(cid: &Cid, bs: &impl Blockstore) -> anyhow::Result<Option<AtomicExecRegistry>> {
@@ -244,9 +442,11 @@ impl AtomicExecRegistry {
     ///
     /// `input` is any data to associate with the returned input ID.
     ///
-    /// If `lock` is set to `true` then the method automatically locks
-    /// the supplied state; otherwise it just captures the state CIDs
-    /// to check against when calling
+    /// `lock` selects what, if anything, the method does to the
+    /// supplied state: [`LockMode::Shared`] or
+    /// [`LockMode::Exclusive`] locks it right away (in the
+    /// corresponding mode), while [`LockMode::Unlocked`] just
+    /// captures the state CIDs to check against when calling
     /// [`prepare_atomic_exec`](Self::prepare_atomic_exec). In that
     /// case, the caller is responsible for flushing the supplied
     /// lockable state to the blockstore.
@@ -255,17 +455,17 @@ impl AtomicExecRegistry {
         bs: &impl Blockstore,
         state: impl IntoIterator<Item = &'a mut S>,
         input: AtomicInput,
-        lock: bool,
+        lock: LockMode,
     ) -> anyhow::Result<AtomicInputID>
     where
         S: LockableState + 'a,
     {
         // Optionally lock the state and compute its CIDs
         let unlocked_state_cids = state.into_iter().try_fold(Vec::new(), |mut v, s| {
-            if lock {
-                s.lock()?;
-            } else {
-                v.push(s.cid());
+            match lock {
+                LockMode::Unlocked => v.push(s.cid()),
+                LockMode::Shared => s.lock_shared()?,
+                LockMode::Exclusive => s.lock_exclusive()?,
             }
             anyhow::Ok(v)
         })?;
@@ -319,11 +519,12 @@ impl AtomicExecRegistry {
             Ok(v)
         })?;
 
-        // Get the state and ensure it's unlocked
+        // Get the state and ensure it's unlocked, whichever mode it
+        // was locked in
         let state_iter = input_fn(input);
         state_iter.for_each(|s| {
             if s.is_locked() {
-                s.unlock().unwrap();
+                s.unlock().or_else(|_| s.unlock_shared()).unwrap();
             }
         });
 
@@ -338,6 +539,12 @@ impl AtomicExecRegistry {
     ///
     /// Every executing actor should agree on the supplied input IDs
     /// `input_ids`, which should include the supplied `own_input_id`.
+    /// Each peer input ID carries the protocol version of the
+    /// registry that produced it; if any of them disagrees with this
+    /// registry's [`protocol_version`](Self::protocol_version), the
+    /// method fails via [`check_compatible`](Self::check_compatible)
+    /// rather than deriving an execution ID the peer could never
+    /// agree on.
     ///
     /// The supplied closure `input_fn` receives the data associated
     /// with `own_input_id`, interprets the data, and returns it
@@ -345,7 +552,10 @@ impl AtomicExecRegistry {
     /// lockable state must match the one previously supplied to the
     /// corresponding invocation of
     /// [`init_atomic_exec`](Self::init_atomic_exec). Any unlocked
-    /// piece of the state is automatically locked by the method.
+    /// piece of the state is locked by the method according to
+    /// `lock`; a piece of state that is already locked (shared or
+    /// exclusive) is left as is, so a shared lock is never upgraded
+    /// to exclusive here.
     ///
     /// The supplied closure `output_fn` receives the data
     /// interpretation returned by `input_fn` and returns any data to
@@ -354,13 +564,20 @@ impl AtomicExecRegistry {
         &mut self,
         bs: &impl Blockstore,
         own_input_id: AtomicInputID,
-        input_ids: &HashMap<IPCAddress, AtomicInputID>,
+        input_ids: &HashMap<IPCAddress, AtomicInputIdent>,
         input_fn: impl FnOnce(AtomicInput) -> (I, Box<dyn Iterator<Item = &'a mut S>>),
         output_fn: impl FnOnce(I) -> anyhow::Result<AtomicOutput>,
+        lock: LockMode,
     ) -> anyhow::Result<AtomicExecID>
     where
         S: 'a + LockableState,
     {
+        // Check that every peer agrees on the protocol semantics
+        // before deriving an execution ID
+        for ident in input_ids.values() {
+            self.check_compatible(ident.protocol_version)?;
+        }
+
         // Consume own input ID and retrieve the associated data
         let AtomicInputEntry {
             unlocked_state_cids,
@@ -374,11 +591,16 @@ impl AtomicExecRegistry {
         })?;
 
         // Get the input and the state; check that the state has not
-        // changed and ensure it is locked
+        // changed and ensure it is locked, without ever upgrading an
+        // already shared-locked piece of state to exclusive
         let (input, state_iter) = input_fn(input);
         let unlocked_state_cid_iter = state_iter.filter(|s| !s.is_locked()).map(|s| {
             let cid = s.cid();
-            s.lock().unwrap();
+            match lock {
+                LockMode::Unlocked => {}
+                LockMode::Shared => s.lock_shared().unwrap(),
+                LockMode::Exclusive => s.lock_exclusive().unwrap(),
+            }
             cid
         });
         if !unlocked_state_cid_iter.eq(unlocked_state_cids) {
@@ -387,7 +609,7 @@ impl AtomicExecRegistry {
 
         // Compute the atomic execution ID; produce and store the
         // output
-        let exec_id = Self::compute_exec_id(input_ids);
+        let exec_id = self.compute_exec_id(input_ids);
         self.exec_ids.modify(bs, |m| {
             let k = BytesKey::from(exec_id.bytes());
             let output = output_fn(input)?;
@@ -431,9 +653,14 @@ impl AtomicExecRegistry {
             Ok(v)
         })?;
 
-        // Get the output and the state; unlock the state
+        // Get the output and the state; unlock the state, whichever
+        // mode it was locked in
         let (output, state_iter) = output_fn(output);
-        state_iter.for_each(|s| s.unlock().unwrap());
+        state_iter.for_each(|s| {
+            if s.is_locked() {
+                s.unlock().or_else(|_| s.unlock_shared()).unwrap();
+            }
+        });
 
         // Apply the output and return the result
         let res = apply_fn(output)?;
@@ -468,9 +695,13 @@ impl AtomicExecRegistry {
             Ok(v)
         })?;
 
-        // Get and unlock the state
+        // Get and unlock the state, whichever mode it was locked in
         let (output, state_iter) = output_fn(output);
-        state_iter.for_each(|s| s.unlock().unwrap());
+        state_iter.for_each(|s| {
+            if s.is_locked() {
+                s.unlock().or_else(|_| s.unlock_shared()).unwrap();
+            }
+        });
 
         // Rollback using the output
         rollback_fn(output);
@@ -487,6 +718,11 @@ impl AtomicExecRegistry {
         self.nonce += 1; // ensure uniqueness of the input ID
 
         let mut h = Blake2b256::default();
+        // Legacy registries must keep deriving IDs exactly as before
+        // so that already-deployed state stays consistent.
+        if self.protocol_version != LEGACY_PROTOCOL_VERSION {
+            h.update(&RawBytes::serialize(self.protocol_version).unwrap());
+        }
         h.update(&RawBytes::serialize(nonce).unwrap());
         for s in unlocked_state_cids {
             h.update(&RawBytes::serialize(s).unwrap());
@@ -495,9 +731,310 @@ impl AtomicExecRegistry {
         Vec::from(h.finalize()).into()
     }
 
-    fn compute_exec_id(input_ids: &HashMap<IPCAddress, AtomicInputID>) -> AtomicExecID {
+    fn compute_exec_id(&self, input_ids: &HashMap<IPCAddress, AtomicInputIdent>) -> AtomicExecID {
         let mut h = Blake2b256::default();
-        h.update(&RawBytes::serialize(input_ids).unwrap());
+        if self.protocol_version == LEGACY_PROTOCOL_VERSION {
+            // Legacy registries hashed the bare input IDs, without any
+            // protocol version
+            let legacy_ids: HashMap<&IPCAddress, &AtomicInputID> = input_ids
+                .iter()
+                .map(|(addr, ident)| (addr, &ident.input_id))
+                .collect();
+            h.update(&RawBytes::serialize(legacy_ids).unwrap());
+        } else {
+            h.update(&RawBytes::serialize(self.protocol_version).unwrap());
+            h.update(&RawBytes::serialize(input_ids).unwrap());
+        }
         Vec::from(h.finalize()).into()
     }
 }
+
+/// Conversion between a typed atomic execution payload and the
+/// `RawBytes` wire representation used internally by
+/// [`AtomicExecRegistry`].
+///
+/// Blanket-implemented for any `Serialize + DeserializeOwned` type,
+/// so most callers never need to implement it by hand.
+pub trait AtomicPayload: Sized {
+    /// Encodes the payload as `RawBytes`.
+    fn to_raw(&self) -> RawBytes;
+
+    /// Decodes the payload from `RawBytes`.
+    fn from_raw(raw: RawBytes) -> anyhow::Result<Self>;
+}
+
+impl<T> AtomicPayload for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn to_raw(&self) -> RawBytes {
+        RawBytes::serialize(self).expect("failed to serialize atomic payload")
+    }
+
+    fn from_raw(raw: RawBytes) -> anyhow::Result<Self> {
+        raw.deserialize()
+            .map_err(|e| anyhow::anyhow!("failed to decode atomic payload: {}", e))
+    }
+}
+
+/// Typed view over an [`AtomicExecRegistry`] that accepts and hands
+/// back `In`/`Out` values directly, doing the `RawBytes`
+/// (de)serialization internally via [`AtomicPayload`].
+///
+/// The untyped, `RawBytes`-based methods remain available through
+/// [`inner`](Self::inner)/[`inner_mut`](Self::inner_mut) as an escape
+/// hatch, so existing callers of [`AtomicExecRegistry`] are not
+/// broken by this layer.
+///
+/// A malformed payload (one that was not produced by this same
+/// layer) surfaces as an `anyhow::Result` error rather than a panic;
+/// depending on how far a `prepare_atomic_exec` call had already
+/// progressed when the decode failed, that error may be reported as
+/// a state CID mismatch instead of a decode error.
+///
+/// That decode failure is not merely cosmetic: by the time
+/// `In::from_raw`/`Out::from_raw` runs, the underlying
+/// [`AtomicExecRegistry`] method has already consumed the input/output
+/// HAMT entry, and every method below falls back to an empty state
+/// iterator on decode failure so it has no `In`/`Out` value to hand to
+/// the caller's `input_fn`/`output_fn`. That means the caller's
+/// `LockableState` is never visited and so never unlocked; a malformed
+/// payload leaves its associated lock stuck permanently, with no way
+/// to retry or unlock it through this layer. This can only happen for
+/// payloads this layer did not itself produce (e.g. state shared with
+/// a pre-upgrade version using an incompatible `In`/`Out` encoding).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TypedAtomicExecRegistry<In, Out> {
+    inner: AtomicExecRegistry,
+    #[serde(skip)]
+    _marker: std::marker::PhantomData<fn() -> (In, Out)>,
+}
+impl<In, Out> Cbor for TypedAtomicExecRegistry<In, Out> {}
+
+impl<In, Out> TypedAtomicExecRegistry<In, Out>
+where
+    In: AtomicPayload,
+    Out: AtomicPayload,
+{
+    /// Constructs a new instance of the typed atomic execution
+    /// registry.
+    pub fn new(bs: &impl Blockstore) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner: AtomicExecRegistry::new(bs)?,
+            _marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Loads the typed atomic execution registry from the supplied
+    /// blockstore by its CID.
+    pub fn load(
+        cid: &Cid,
+        bs: &impl Blockstore,
+    ) -> anyhow::Result<Option<TypedAtomicExecRegistry<In, Out>>> {
+        bs.get_cbor(cid)
+    }
+
+    /// Flushes the typed atomic execution registry to the supplied
+    /// blockstore and return its CID.
+    pub fn flush(&self, bs: &impl Blockstore) -> anyhow::Result<Cid> {
+        let cid = bs.put_cbor(&self, Code::Blake2b256)?;
+        Ok(cid)
+    }
+
+    /// Gives access to the untyped registry underlying this typed
+    /// view.
+    pub fn inner(&self) -> &AtomicExecRegistry {
+        &self.inner
+    }
+
+    /// Gives mutable access to the untyped registry underlying this
+    /// typed view.
+    pub fn inner_mut(&mut self) -> &mut AtomicExecRegistry {
+        &mut self.inner
+    }
+
+    /// Typed equivalent of
+    /// [`AtomicExecRegistry::init_atomic_exec`].
+    pub fn init_atomic_exec<'a, S>(
+        &mut self,
+        bs: &impl Blockstore,
+        state: impl IntoIterator<Item = &'a mut S>,
+        input: In,
+        lock: LockMode,
+    ) -> anyhow::Result<AtomicInputID>
+    where
+        S: LockableState + 'a,
+    {
+        self.inner.init_atomic_exec(bs, state, input.to_raw(), lock)
+    }
+
+    /// Typed equivalent of
+    /// [`AtomicExecRegistry::cancel_atomic_exec`].
+    ///
+    /// A decode failure leaves the associated lock stuck forever; see
+    /// the [struct-level documentation](Self) for why.
+    pub fn cancel_atomic_exec<'a, S>(
+        &mut self,
+        bs: &impl Blockstore,
+        input_id: AtomicInputID,
+        input_fn: impl FnOnce(In) -> Box<dyn Iterator<Item = &'a mut S>>,
+    ) -> anyhow::Result<()>
+    where
+        S: LockableState + 'a,
+    {
+        let mut decode_err = None;
+        self.inner.cancel_atomic_exec(bs, input_id, |raw| match In::from_raw(raw) {
+            Ok(input) => input_fn(input),
+            Err(e) => {
+                decode_err = Some(e);
+                Box::new(std::iter::empty()) as Box<dyn Iterator<Item = &'a mut S>>
+            }
+        })?;
+        match decode_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Typed equivalent of
+    /// [`AtomicExecRegistry::prepare_atomic_exec`].
+    ///
+    /// A decode failure leaves the associated lock stuck forever; see
+    /// the [struct-level documentation](Self) for why.
+    pub fn prepare_atomic_exec<'a, S, I>(
+        &mut self,
+        bs: &impl Blockstore,
+        own_input_id: AtomicInputID,
+        input_ids: &HashMap<IPCAddress, AtomicInputIdent>,
+        input_fn: impl FnOnce(In) -> (I, Box<dyn Iterator<Item = &'a mut S>>),
+        output_fn: impl FnOnce(I) -> anyhow::Result<Out>,
+        lock: LockMode,
+    ) -> anyhow::Result<AtomicExecID>
+    where
+        S: 'a + LockableState,
+    {
+        self.inner.prepare_atomic_exec(
+            bs,
+            own_input_id,
+            input_ids,
+            |raw| match In::from_raw(raw) {
+                Ok(input) => {
+                    let (i, state_iter) = input_fn(input);
+                    (Ok(i), state_iter)
+                }
+                Err(e) => (Err(e), Box::new(std::iter::empty()) as Box<dyn Iterator<Item = &'a mut S>>),
+            },
+            |res: anyhow::Result<I>| Ok(output_fn(res?)?.to_raw()),
+            lock,
+        )
+    }
+
+    /// Typed equivalent of
+    /// [`AtomicExecRegistry::commit_atomic_exec`].
+    ///
+    /// A decode failure leaves the associated lock stuck forever; see
+    /// the [struct-level documentation](Self) for why.
+    pub fn commit_atomic_exec<'a, S, O, R>(
+        &mut self,
+        bs: &impl Blockstore,
+        exec_id: AtomicExecID,
+        output_fn: impl FnOnce(Out) -> (O, Box<dyn Iterator<Item = &'a mut S>>),
+        apply_fn: impl FnOnce(O) -> anyhow::Result<R>,
+    ) -> anyhow::Result<R>
+    where
+        S: 'a + LockableState,
+    {
+        self.inner.commit_atomic_exec(
+            bs,
+            exec_id,
+            |raw| match Out::from_raw(raw) {
+                Ok(output) => {
+                    let (o, state_iter) = output_fn(output);
+                    (Ok(o), state_iter)
+                }
+                Err(e) => (Err(e), Box::new(std::iter::empty()) as Box<dyn Iterator<Item = &'a mut S>>),
+            },
+            |res: anyhow::Result<O>| apply_fn(res?),
+        )
+    }
+
+    /// Typed equivalent of
+    /// [`AtomicExecRegistry::rollback_atomic_exec`].
+    ///
+    /// A decode failure leaves the associated lock stuck forever; see
+    /// the [struct-level documentation](Self) for why.
+    pub fn rollback_atomic_exec<'a, S, O>(
+        &mut self,
+        bs: &impl Blockstore,
+        exec_id: AtomicExecID,
+        output_fn: impl FnOnce(Out) -> (O, Box<dyn Iterator<Item = &'a mut S>>),
+        rollback_fn: impl FnOnce(O),
+    ) -> anyhow::Result<()>
+    where
+        S: 'a + LockableState,
+    {
+        let mut decode_err = None;
+        self.inner.rollback_atomic_exec(
+            bs,
+            exec_id,
+            |raw| match Out::from_raw(raw) {
+                Ok(output) => {
+                    let (o, state_iter) = output_fn(output);
+                    (Ok(o), state_iter)
+                }
+                Err(e) => (Err(e), Box::new(std::iter::empty()) as Box<dyn Iterator<Item = &'a mut S>>),
+            },
+            |res: anyhow::Result<O>| match res {
+                Ok(o) => rollback_fn(o),
+                // An undecodable output has no typed value to hand to
+                // `rollback_fn`; report the decode error instead
+                Err(e) => decode_err = Some(e),
+            },
+        )?;
+        match decode_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_lock_excludes_shared_and_exclusive() {
+        let mut s = AtomicInputState::new(42u64);
+        s.lock_exclusive().unwrap();
+        assert!(s.is_locked());
+        assert!(s.lock_exclusive().is_err());
+        assert!(s.lock_shared().is_err());
+        s.unlock().unwrap();
+        assert!(!s.is_locked());
+    }
+
+    #[test]
+    fn shared_lock_allows_multiple_holders_but_not_exclusive() {
+        let mut s = AtomicInputState::new(42u64);
+        s.lock_shared().unwrap();
+        s.lock_shared().unwrap();
+        assert!(s.is_locked());
+        assert!(s.lock_exclusive().is_err());
+        // One holder remains after a single release.
+        s.unlock_shared().unwrap();
+        assert!(s.is_locked());
+        s.unlock_shared().unwrap();
+        assert!(!s.is_locked());
+    }
+
+    #[test]
+    fn unlock_fails_on_shared_state_and_unlock_shared_fails_on_exclusive_state() {
+        let mut shared = AtomicInputState::new(42u64);
+        shared.lock_shared().unwrap();
+        assert!(shared.unlock().is_err());
+
+        let mut exclusive = AtomicInputState::new(42u64);
+        exclusive.lock_exclusive().unwrap();
+        assert!(exclusive.unlock_shared().is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::state::State;
+use crate::state::{Commitment, State};
 use fil_actors_runtime::runtime::{ActorCode, Runtime};
 use fil_actors_runtime::{actor_error, cbor, ActorDowncast, ActorError, INIT_ACTOR_ADDR};
 use fvm_ipld_blockstore::Blockstore;
@@ -11,8 +11,12 @@ use num_derive::FromPrimitive;
 use num_traits::{FromPrimitive, Zero};
 
 pub use crate::atomic::{AtomicExecID, AtomicInput, AtomicInputID, AtomicOutput};
-pub use crate::atomic::{AtomicExecRegistry, AtomicInputState, LockableState};
-pub use crate::types::{ConstructorParams, PreCommitParams, RevokeParams};
+pub use crate::atomic::{AtomicExecRegistry, AtomicInputIdent, AtomicInputState, AtomicPayload};
+pub use crate::atomic::{LockMode, LockableState, TypedAtomicExecRegistry};
+pub use crate::atomic::{LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use crate::types::{ConstructorParams, PreCommitParams, RevokeParams, SweepExpiredParams};
+pub use crate::types::{apply_caveats, Caveat, ProtocolParams};
+pub use crate::types::{StatusParams, StatusReturn};
 
 mod atomic;
 mod state;
@@ -27,6 +31,8 @@ pub enum Method {
     Constructor = METHOD_CONSTRUCTOR,
     PreCommit = 2,
     Revoke = 3,
+    SweepExpired = 4,
+    Status = 5,
 }
 
 /// Atomic execution coordinator actor
@@ -72,7 +78,17 @@ impl Actor {
                 },
         } = params;
 
-        let params: PreCommitParams = cbor::deserialize_params(&params)?;
+        let mut params: PreCommitParams = cbor::deserialize_params(&params)?;
+
+        // Run the request through the coordinator's caveat chain
+        // before trusting any of it
+        apply_caveats(
+            &st.caveats,
+            &from,
+            vec![&mut params.commit, &mut params.rollback],
+        )
+        .map_err(|e| actor_error!(illegal_argument; "{}", e))?;
+
         let actors = &params.actors;
         let exec_id = &params.exec_id;
 
@@ -83,25 +99,61 @@ impl Actor {
             ));
         }
 
+        let default_deadline_epochs = st.protocol_params.default_deadline_epochs;
+
         let msgs = rt.transaction(|st: &mut State, rt| {
             st.modify_atomic_exec(rt.store(), exec_id.clone(), actors.clone(), |entry| {
+                // Reject a pre-commitment that arrives after the
+                // execution's deadline has already passed: it is
+                // someone else's job now to call `SweepExpired` and
+                // roll back whoever did commit in time, not to let a
+                // straggler complete a stale execution.
+                if matches!(entry.timeout_epoch, Some(t) if rt.curr_epoch() > t) {
+                    anyhow::bail!("atomic execution pre-commit deadline has passed");
+                }
+
+                // Reject a pre-commitment for an execution that has
+                // already been aborted by a revoke from some other
+                // participant.
+                if entry.aborted {
+                    anyhow::bail!("atomic execution has been aborted");
+                }
+
                 // Record the pre-commitment
-                entry.insert(from, params.commit);
+                entry.commitments.insert(
+                    from,
+                    Commitment {
+                        commit: params.commit,
+                        rollback: params.rollback,
+                    },
+                );
+
+                // Track the earliest timeout requested by any
+                // participant so far, falling back to the
+                // protocol-wide default deadline so an execution
+                // always has one, even if no participant asked for it
+                let timeout_epoch = params
+                    .timeout_epoch
+                    .unwrap_or_else(|| rt.curr_epoch() + default_deadline_epochs);
+                entry.timeout_epoch = Some(match entry.timeout_epoch {
+                    Some(t) => t.min(timeout_epoch),
+                    None => timeout_epoch,
+                });
 
                 // Check if any pre-commitment is missing
                 for actor in actors {
-                    if !entry.contains_key(actor) {
+                    if !entry.commitments.contains_key(actor) {
                         return Ok(None);
                     }
                 }
 
                 // Prepare messages to commit the atomic execution
                 let mut msgs = Vec::new();
-                entry.iter_mut().for_each(|(addr, &mut method)| {
+                entry.commitments.iter().for_each(|(addr, commitment)| {
                     msgs.push(CrossMsg {
                         msg: StorableMsg {
                             to: addr.to_owned(),
-                            method,
+                            method: commitment.commit,
                             params: exec_id.clone(),
                             ..Default::default()
                         },
@@ -144,10 +196,17 @@ impl Actor {
         }
     }
 
-    /// Removes a pre-commitment from an actor to perform an atomic
-    /// execution. This method is to be invoked by a wrapped crossnet
-    /// message originating in one of the execution actors involved in
-    /// the atomic execution.
+    /// Aborts an atomic execution on behalf of one of its participants.
+    /// This method is to be invoked by a wrapped crossnet message
+    /// originating in one of the execution actors involved in the
+    /// atomic execution. A revoke from any single participant aborts
+    /// the whole execution: every actor that already pre-committed is
+    /// sent a rollback `CrossMsg` (using its own recorded rollback
+    /// method), mirroring the way `pre_commit` fans out commit
+    /// messages to every participant on success. The execution is then
+    /// marked aborted so a pre-commitment that arrives late for the
+    /// same `exec_id` is rejected rather than silently starting a new
+    /// execution.
     fn revoke<BS, RT>(rt: &mut RT, params: ApplyMsgParams) -> Result<(), ActorError>
     where
         BS: Blockstore,
@@ -166,7 +225,13 @@ impl Actor {
                 },
         } = params;
 
-        let params: RevokeParams = cbor::deserialize_params(&params)?;
+        let mut params: RevokeParams = cbor::deserialize_params(&params)?;
+
+        // Run the request through the coordinator's caveat chain
+        // before trusting any of it
+        apply_caveats(&st.caveats, &from, vec![&mut params.rollback])
+            .map_err(|e| actor_error!(illegal_argument; "{}", e))?;
+
         let actors = &params.actors;
         let exec_id = &params.exec_id;
 
@@ -177,29 +242,60 @@ impl Actor {
             ));
         }
 
-        let msg = rt.transaction(|st: &mut State, rt| {
+        let default_deadline_epochs = st.protocol_params.default_deadline_epochs;
+
+        let msgs = rt.transaction(|st: &mut State, rt| {
             st.modify_atomic_exec(rt.store(), exec_id.clone(), actors.clone(), |entry| {
-                // Remove the pre-commitment
-                entry.remove_entry(&from);
-
-                // Prepare a message to rollback the atomic execution
-                Ok(Some(CrossMsg {
-                    msg: StorableMsg {
-                        to: from,
-                        method: params.rollback,
-                        params: exec_id.clone(),
-                        ..Default::default()
-                    },
-                    wrapped: true,
-                }))
+                // Roll back every actor that already pre-committed, as
+                // well as the revoking actor itself if it revokes before
+                // pre-committing; the latter is not recorded as a
+                // pre-commitment, so `Status` never reports it as one.
+                let mut msgs = Vec::new();
+                if !entry.commitments.contains_key(&from) {
+                    msgs.push(CrossMsg {
+                        msg: StorableMsg {
+                            to: from.to_owned(),
+                            method: params.rollback,
+                            params: exec_id.clone(),
+                            ..Default::default()
+                        },
+                        wrapped: true,
+                    });
+                }
+                entry.commitments.iter().for_each(|(addr, commitment)| {
+                    msgs.push(CrossMsg {
+                        msg: StorableMsg {
+                            to: addr.to_owned(),
+                            method: commitment.rollback,
+                            params: exec_id.clone(),
+                            ..Default::default()
+                        },
+                        wrapped: true,
+                    });
+                });
+
+                // Abort the execution so a late pre-commit can't revive
+                // it
+                entry.aborted = true;
+
+                // An aborted entry must carry a concrete timeout_epoch
+                // even if nobody ever pre-committed, or SweepExpired's
+                // `timeout_epoch.is_some()` guard would never fire and
+                // the tombstone would occupy its in_flight_execs slot
+                // forever.
+                entry
+                    .timeout_epoch
+                    .get_or_insert_with(|| rt.curr_epoch() + default_deadline_epochs);
+
+                Ok(msgs)
             })
             .map_err(|e| {
                 e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to update registry")
             })
         })?;
 
-        if let Some(msg) = msg {
-            // Send the message to rollback the atomic execution
+        // Send the messages to rollback the atomic execution
+        for msg in msgs {
             rt.send(
                 st.ipc_gateway_address,
                 ipc_gateway::Method::SendCross as MethodNum,
@@ -210,6 +306,147 @@ impl Actor {
 
         Ok(())
     }
+
+    /// Permissionlessly sweeps an atomic execution that has been
+    /// pre-committed past its recorded timeout, rolling back every
+    /// actor that already pre-committed. This lets anyone (typically
+    /// a relayer or one of the stuck participants) reclaim locked
+    /// state when a participant never shows up to complete a
+    /// `PreCommit`, rather than leaving it locked forever. An entry
+    /// already aborted by `revoke` is just removed, without rolling
+    /// back a second time, since `revoke` always gives such an entry a
+    /// concrete `timeout_epoch` so its `in_flight_execs` slot is
+    /// eventually reclaimed. Returns `true` if the execution was
+    /// indeed expired and swept.
+    fn sweep_expired<BS, RT>(rt: &mut RT, params: SweepExpiredParams) -> Result<bool, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        let st: State = rt.state()?;
+
+        rt.validate_immediate_caller_accept_any()?;
+
+        let SweepExpiredParams { actors, exec_id } = params;
+
+        // `SweepExpired` is permissionless, so it must never be able to
+        // conjure a registry entry for an `(exec_id, actors)` pair
+        // nobody pre-committed: `modify_atomic_exec` inserts a fresh
+        // entry (and counts it against `max_in_flight_execs`) for any
+        // exec_id it hasn't seen before, which would let anyone pin
+        // bogus entries for free. Check via a read-only lookup first,
+        // and only reach `modify_atomic_exec` once there is a real,
+        // actually-expired entry to roll back.
+        let entry = st
+            .get_atomic_exec(rt.store(), exec_id.clone(), actors.clone())
+            .map_err(|e| e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to read registry"))?;
+        if !matches!(entry.timeout_epoch, Some(t) if rt.curr_epoch() > t) {
+            return Ok(false);
+        }
+
+        let msgs = rt.transaction(|st: &mut State, rt| {
+            st.modify_atomic_exec(rt.store(), exec_id.clone(), actors.clone(), |entry| {
+                let expired = matches!(entry.timeout_epoch, Some(t) if rt.curr_epoch() > t);
+                if !expired {
+                    return Ok(None);
+                }
+
+                // An already-aborted entry was rolled back by `revoke`
+                // already; it is only still around as a tombstone so a
+                // late pre-commit gets rejected. Reclaim its slot
+                // without rolling back a second time.
+                if entry.aborted {
+                    return Ok(Some(Vec::new()));
+                }
+
+                // Roll back every actor that already pre-committed
+                let mut msgs = Vec::new();
+                entry.commitments.iter().for_each(|(addr, commitment)| {
+                    msgs.push(CrossMsg {
+                        msg: StorableMsg {
+                            to: addr.to_owned(),
+                            method: commitment.rollback,
+                            params: exec_id.clone(),
+                            ..Default::default()
+                        },
+                        wrapped: true,
+                    });
+                });
+                Ok(Some(msgs))
+            })
+            .map_err(|e| {
+                e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to update registry")
+            })
+        })?;
+
+        match msgs {
+            Some(msgs) => {
+                // Send the messages to roll back the atomic execution
+                for msg in msgs {
+                    rt.send(
+                        st.ipc_gateway_address,
+                        ipc_gateway::Method::SendCross as MethodNum,
+                        RawBytes::serialize(msg)?,
+                        TokenAmount::zero(),
+                    )?;
+                }
+
+                // Remove the atomic execution entry
+                rt.transaction(|st: &mut State, rt| {
+                    st.rm_atomic_exec(rt.store(), exec_id.clone(), actors.clone())
+                        .map_err(|e| {
+                            e.downcast_default(
+                                ExitCode::USR_ILLEGAL_STATE,
+                                "failed to remove atomic exec from registry",
+                            )
+                        })
+                })?;
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Reports, without mutating any state, which of the actors
+    /// participating in an atomic execution have pre-committed so far
+    /// and which are still pending, along with the execution's
+    /// recorded timeout (if any) and whether it has already been
+    /// aborted by a `Revoke`. Lets relayers and execution actors poll
+    /// progress cheaply instead of blindly resubmitting pre-commits or
+    /// waiting indefinitely for an outcome message.
+    fn status<BS, RT>(rt: &mut RT, params: StatusParams) -> Result<StatusReturn, ActorError>
+    where
+        BS: Blockstore,
+        RT: Runtime<BS>,
+    {
+        rt.validate_immediate_caller_accept_any()?;
+
+        let st: State = rt.state()?;
+
+        let StatusParams { actors, exec_id } = params;
+
+        let entry = st
+            .get_atomic_exec(rt.store(), exec_id, actors.clone())
+            .map_err(|e| e.downcast_default(ExitCode::USR_ILLEGAL_STATE, "failed to read registry"))?;
+
+        let pending = actors
+            .into_iter()
+            .filter(|actor| !entry.commitments.contains_key(actor))
+            .collect();
+        let committed = entry
+            .commitments
+            .into_iter()
+            .map(|(addr, commitment)| (addr, commitment.commit))
+            .collect();
+
+        Ok(StatusReturn {
+            committed,
+            pending,
+            timeout_epoch: entry.timeout_epoch,
+            aborted: entry.aborted,
+        })
+    }
 }
 
 impl ActorCode for Actor {
@@ -235,6 +472,14 @@ impl ActorCode for Actor {
                 Self::revoke(rt, cbor::deserialize_params(params)?)?;
                 Ok(RawBytes::default())
             }
+            Some(Method::SweepExpired) => {
+                let res = Self::sweep_expired(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
+            Some(Method::Status) => {
+                let res = Self::status(rt, cbor::deserialize_params(params)?)?;
+                Ok(RawBytes::serialize(res)?)
+            }
             None => Err(actor_error!(unhandled_message; "Invalid method")),
         }
     }
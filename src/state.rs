@@ -4,6 +4,7 @@ use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::Cbor;
 use fvm_ipld_hamt::BytesKey;
 use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::MethodNum;
 use ipc_gateway::IPCAddress;
 use primitives::{TCid, THamt};
@@ -11,18 +12,69 @@ use serde::{Deserialize, Serialize};
 use serde_tuple::{Deserialize_tuple, Serialize_tuple};
 use std::collections::{HashMap, HashSet};
 
-use crate::{AtomicExecID, ConstructorParams};
+use crate::{AtomicExecID, Caveat, ConstructorParams, ProtocolParams};
 
 #[derive(Serialize, Deserialize)]
 pub struct State {
     pub ipc_gateway_address: Address,
     pub registry: RegistryCid, // (exec_id, actors) -> pre-commitments
+    /// Ordered caveat chain attenuating who may drive this coordinator
+    /// instance and which methods it may call back. See
+    /// [`crate::types::apply_caveats`].
+    #[serde(default)]
+    pub caveats: Vec<Caveat>,
+    /// Tunable protocol limits for this coordinator instance.
+    ///
+    /// Defaults to unbounded limits (see [`ProtocolParams::default`])
+    /// so that state created before this field existed keeps
+    /// deserializing after an in-place actor upgrade.
+    #[serde(default)]
+    pub protocol_params: ProtocolParams,
+    /// Number of distinct atomic executions currently tracked in
+    /// `registry`, maintained alongside it so `max_in_flight_execs`
+    /// can be enforced without walking the whole HAMT.
+    #[serde(default)]
+    pub in_flight_execs: u64,
 }
 impl Cbor for State {}
 
 // TODO: Use hash/CID as the key?
 type RegistryCid = TCid<THamt<RegistryKey, RegistryEntry>>;
-type RegistryEntry = HashMap<IPCAddress, MethodNum>;
+
+/// Commit and rollback methods an actor recorded when pre-committing
+/// to an atomic execution.
+#[derive(Clone, PartialEq, Eq, Default, Serialize_tuple, Deserialize_tuple)]
+pub struct Commitment {
+    pub commit: MethodNum,
+    pub rollback: MethodNum,
+}
+
+/// Pre-commitments collected so far for an atomic execution, along
+/// with its earliest recorded timeout (a participant's requested
+/// timeout, or the protocol's default deadline where none was given).
+///
+/// `timeout_epoch` is `#[serde(default)]` so registry entries written
+/// before this field existed keep deserializing in place. This does
+/// not cover the earlier shape change from a bare `MethodNum` to
+/// `Commitment`: an in-place upgrade from that shape still requires
+/// the registry to be empty (or migrated) before deploying, since
+/// tuple encoding decodes positionally.
+#[derive(Clone, PartialEq, Default, Serialize_tuple, Deserialize_tuple)]
+pub struct RegistryEntry {
+    pub commitments: HashMap<IPCAddress, Commitment>,
+    /// Chain epoch beyond which this execution may be swept (rolled
+    /// back) by anyone, even though not all participants have
+    /// pre-committed yet.
+    #[serde(default)]
+    pub timeout_epoch: Option<ChainEpoch>,
+    /// Set once any participant has revoked its pre-commitment,
+    /// aborting the whole execution. Kept around (rather than having
+    /// the entry simply disappear) so a pre-commitment that arrives
+    /// late for this `exec_id` can be rejected instead of silently
+    /// starting a fresh execution.
+    #[serde(default)]
+    pub aborted: bool,
+}
 
 #[derive(Clone, PartialEq, Serialize_tuple, Deserialize_tuple)]
 pub struct RegistryKey {
@@ -36,29 +88,69 @@ impl State {
         Ok(State {
             registry: TCid::new_hamt(store)?,
             ipc_gateway_address: params.ipc_gateway_address,
+            caveats: params.caveats,
+            protocol_params: params.protocol_params,
+            in_flight_execs: 0,
         })
     }
 
     /// Modifies the atomic execution entry associated with the atomic
-    /// execution ID and the actors.
+    /// execution ID and the actors. Rejects `actors` sets larger than
+    /// `max_participants` (bounding the commit/rollback fan-out), and
+    /// rejects a previously-unseen `exec_id` once `max_in_flight_execs`
+    /// distinct entries are already tracked, so a flood of partial
+    /// pre-commits cannot grow the registry unboundedly. Enforced here,
+    /// rather than by each caller, so no call site can forget either
+    /// check.
     pub fn modify_atomic_exec<BS: Blockstore, R>(
         &mut self,
         store: &BS,
         exec_id: AtomicExecID,
         actors: HashSet<IPCAddress>,
-        f: impl FnOnce(&mut HashMap<IPCAddress, MethodNum>) -> anyhow::Result<R>,
+        f: impl FnOnce(&mut RegistryEntry) -> anyhow::Result<R>,
     ) -> anyhow::Result<R> {
+        if actors.len() as u64 > self.protocol_params.max_participants {
+            anyhow::bail!("too many participants in atomic execution");
+        }
+
+        let max_in_flight_execs = self.protocol_params.max_in_flight_execs;
+        let in_flight_execs = &mut self.in_flight_execs;
         self.registry.modify(store, |registry| {
             let k = BytesKey::from(RegistryKey { exec_id, actors }.marshal_cbor()?);
-            let mut entry = registry
-                .get(&k)?
-                .map_or_else(HashMap::new, |e| e.to_owned());
+            let mut entry = match registry.get(&k)? {
+                Some(e) => e.to_owned(),
+                None => {
+                    if *in_flight_execs >= max_in_flight_execs {
+                        anyhow::bail!("too many in-flight atomic executions");
+                    }
+                    *in_flight_execs += 1;
+                    RegistryEntry::default()
+                }
+            };
             let res = f(&mut entry)?;
             registry.set(k, entry)?;
             Ok(res)
         })
     }
 
+    /// Reads, without mutating it, the atomic execution entry
+    /// associated with the atomic execution ID and the actors. Returns
+    /// the default (empty) entry if no pre-commitment has been
+    /// recorded yet.
+    pub fn get_atomic_exec<BS: Blockstore>(
+        &self,
+        store: &BS,
+        exec_id: AtomicExecID,
+        actors: HashSet<IPCAddress>,
+    ) -> anyhow::Result<RegistryEntry> {
+        let k = BytesKey::from(RegistryKey { exec_id, actors }.marshal_cbor()?);
+        Ok(self
+            .registry
+            .load(store)?
+            .get(&k)?
+            .map_or_else(RegistryEntry::default, |e| e.to_owned()))
+    }
+
     /// Removes the atomic execution entry associated with the atomic
     /// execution ID and the actors.
     pub fn rm_atomic_exec<BS: Blockstore>(
@@ -68,10 +160,103 @@ impl State {
         actors: HashSet<IPCAddress>,
     ) -> anyhow::Result<()> {
         let k = BytesKey::from(RegistryKey { exec_id, actors }.marshal_cbor()?);
+        let mut existed = false;
         self.registry.update(store, |registry| {
-            registry.delete(&k)?;
+            existed = registry.delete(&k)?.is_some();
             Ok(())
         })?;
+        if existed {
+            self.in_flight_execs = self.in_flight_execs.saturating_sub(1);
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fvm_ipld_blockstore::MemoryBlockstore;
+
+    fn new_state(bs: &MemoryBlockstore, protocol_params: ProtocolParams) -> State {
+        State::new(
+            bs,
+            ConstructorParams {
+                ipc_gateway_address: Address::new_id(100),
+                caveats: Vec::new(),
+                protocol_params,
+            },
+        )
+        .unwrap()
+    }
+
+    fn protocol_params(max_participants: u64, max_in_flight_execs: u64) -> ProtocolParams {
+        ProtocolParams {
+            max_participants,
+            default_deadline_epochs: 100,
+            max_in_flight_execs,
+        }
+    }
+
+    #[test]
+    fn modify_atomic_exec_counts_and_uncounts_distinct_entries() {
+        let bs = MemoryBlockstore::default();
+        let mut st = new_state(&bs, protocol_params(10, 10));
+
+        st.modify_atomic_exec(&bs, AtomicExecID::default(), HashSet::new(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 1);
+
+        // Modifying the same entry again must not double-count it.
+        st.modify_atomic_exec(&bs, AtomicExecID::default(), HashSet::new(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 1);
+
+        st.rm_atomic_exec(&bs, AtomicExecID::default(), HashSet::new())
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 0);
+    }
+
+    #[test]
+    fn aborted_entry_with_a_timeout_epoch_frees_its_in_flight_slot_on_removal() {
+        // Mirrors what `revoke` and `sweep_expired` do in `lib.rs`: a
+        // revoke-only entry (nobody ever pre-committed) must still get
+        // a concrete `timeout_epoch` so it can later be reaped, rather
+        // than permanently pinning an `in_flight_execs` slot.
+        let bs = MemoryBlockstore::default();
+        let mut st = new_state(&bs, protocol_params(10, 1));
+
+        st.modify_atomic_exec(&bs, AtomicExecID::default(), HashSet::new(), |entry| {
+            entry.aborted = true;
+            entry.timeout_epoch.get_or_insert(1);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(st.in_flight_execs, 1);
+
+        st.rm_atomic_exec(&bs, AtomicExecID::default(), HashSet::new())
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 0);
+
+        // The slot is free again, so a fresh execution can use it.
+        st.modify_atomic_exec(&bs, vec![1].into(), HashSet::new(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 1);
+    }
+
+    #[test]
+    fn modify_atomic_exec_rejects_too_many_in_flight_execs() {
+        let bs = MemoryBlockstore::default();
+        let mut st = new_state(&bs, protocol_params(10, 1));
+
+        st.modify_atomic_exec(&bs, vec![1].into(), HashSet::new(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(st.in_flight_execs, 1);
+
+        // A second, distinct exec_id exceeds the cap and must not be
+        // persisted or counted.
+        assert!(st
+            .modify_atomic_exec(&bs, vec![2].into(), HashSet::new(), |_| Ok(()))
+            .is_err());
+        assert_eq!(st.in_flight_execs, 1);
+    }
+}
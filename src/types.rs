@@ -1,15 +1,120 @@
 use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
 use fvm_ipld_encoding::Cbor;
 use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::MethodNum;
 use ipc_gateway::IPCAddress;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 use crate::AtomicExecID;
 
 #[derive(Serialize_tuple, Deserialize_tuple)]
 pub struct ConstructorParams {
     pub ipc_gateway_address: Address,
+    /// Ordered chain of caveats attenuating who may drive this
+    /// coordinator instance and which methods it may call back.
+    /// Applied, in order, to every `pre_commit`/`revoke` request.
+    pub caveats: Vec<Caveat>,
+    /// Tunable protocol limits for this coordinator instance.
+    pub protocol_params: ProtocolParams,
+}
+
+/// Tunable protocol limits for a coordinator instance, analogous to a
+/// VM's swappable network-parameters object. Making these explicit
+/// construction-time parameters, rather than hard-coded constants,
+/// lets the same actor code be deployed with different safety
+/// envelopes on, e.g., devnet vs mainnet subnets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct ProtocolParams {
+    /// Maximum number of actors that may participate in a single
+    /// atomic execution, bounding the commit/rollback fan-out that
+    /// `pre_commit` and `revoke` can trigger.
+    pub max_participants: u64,
+    /// Default number of epochs after pre-commit before an execution
+    /// whose pre-commitments did not specify a `timeout_epoch` may be
+    /// swept.
+    pub default_deadline_epochs: ChainEpoch,
+    /// Maximum number of distinct atomic executions that may be
+    /// tracked in the registry at once (from the first pre-commit or
+    /// revoke for an execution until its entry is removed, including
+    /// aborted entries not yet swept), bounding how much a flood of
+    /// partial pre-commits can grow the registry.
+    pub max_in_flight_execs: u64,
+}
+
+impl Default for ProtocolParams {
+    /// Matches the unbounded behavior the coordinator had before these
+    /// limits existed, so a `State` created prior to this field's
+    /// introduction keeps working unmodified after an in-place actor
+    /// upgrade, rather than deserializing into limits nobody chose.
+    fn default() -> Self {
+        ProtocolParams {
+            max_participants: u64::MAX,
+            // Halved so that `rt.curr_epoch() + default_deadline_epochs`
+            // can never overflow `ChainEpoch`.
+            default_deadline_epochs: ChainEpoch::MAX / 2,
+            max_in_flight_execs: u64::MAX,
+        }
+    }
+}
+
+/// A capability caveat restricting or rewriting a `pre_commit`/`revoke`
+/// request before it is acted upon, in the spirit of an attenuated
+/// sturdy-ref: each caveat either rejects the request outright or
+/// transforms it, and only the result of the whole chain is trusted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Caveat {
+    /// The cross-message's `from` actor must be one of these.
+    MemberOf(Vec<IPCAddress>),
+    /// The requested commit/rollback method must be one of these.
+    AllowMethods(Vec<MethodNum>),
+    /// Substitute `to` for `from` wherever a participant is allowed to
+    /// be called back on `from`.
+    Rewrite { from: MethodNum, to: MethodNum },
+}
+
+impl Caveat {
+    /// Checks `from` and rewrites `methods` in place according to this
+    /// caveat, failing if the caveat rejects the request.
+    fn check_and_rewrite(&self, from: &IPCAddress, methods: &mut [&mut MethodNum]) -> anyhow::Result<()> {
+        match self {
+            Caveat::MemberOf(members) => {
+                if !members.contains(from) {
+                    anyhow::bail!("{} is not a member of the allowed caveat set", from);
+                }
+            }
+            Caveat::AllowMethods(allowed) => {
+                for method in methods.iter() {
+                    if !allowed.contains(method) {
+                        anyhow::bail!("method {} is not allowed by caveat", method);
+                    }
+                }
+            }
+            Caveat::Rewrite { from: from_method, to } => {
+                for method in methods.iter_mut() {
+                    if **method == *from_method {
+                        **method = *to;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `from` and `methods` through the ordered caveat chain,
+/// rewriting `methods` in place. Fails with the reason given by the
+/// first caveat that rejects the request.
+pub fn apply_caveats(
+    caveats: &[Caveat],
+    from: &IPCAddress,
+    mut methods: Vec<&mut MethodNum>,
+) -> anyhow::Result<()> {
+    for caveat in caveats {
+        caveat.check_and_rewrite(from, &mut methods)?;
+    }
+    Ok(())
 }
 
 /// Parameters for `pre_commit` method of IPC atomic exec coordinator.
@@ -22,6 +127,16 @@ pub struct PreCommitParams {
     /// Method to call back to commit atomic execution.
     // TODO: Revise based on the outcomes of FIP-0042.
     pub commit: MethodNum,
+    /// Method to call back to roll back the atomic execution if it
+    /// is aborted by a `Revoke` or swept via `SweepExpired` after
+    /// `timeout_epoch`.
+    // TODO: Revise based on the outcomes of FIP-0042.
+    pub rollback: MethodNum,
+    /// Chain epoch beyond which this pre-commitment is considered
+    /// stale and may be unilaterally rolled back by anyone calling
+    /// `SweepExpired`, rather than waiting forever for a missing
+    /// participant.
+    pub timeout_epoch: Option<ChainEpoch>,
 }
 impl Cbor for PreCommitParams {}
 
@@ -37,3 +152,44 @@ pub struct RevokeParams {
     pub rollback: MethodNum,
 }
 impl Cbor for RevokeParams {}
+
+/// Parameters for `sweep_expired` method of IPC atomic exec
+/// coordinator.
+#[derive(Clone, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct SweepExpiredParams {
+    /// Actors participating in the atomic execution.
+    pub actors: HashSet<IPCAddress>,
+    /// Atomic execution ID.
+    pub exec_id: AtomicExecID,
+}
+impl Cbor for SweepExpiredParams {}
+
+/// Parameters for `status` method of IPC atomic exec coordinator.
+#[derive(Clone, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct StatusParams {
+    /// Actors participating in the atomic execution.
+    pub actors: HashSet<IPCAddress>,
+    /// Atomic execution ID.
+    pub exec_id: AtomicExecID,
+}
+impl Cbor for StatusParams {}
+
+/// Return value of `status` method of IPC atomic exec coordinator.
+#[derive(Clone, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct StatusReturn {
+    /// Actors that have already pre-committed, along with the method
+    /// each recorded to commit the atomic execution.
+    pub committed: HashMap<IPCAddress, MethodNum>,
+    /// Actors that have not pre-committed yet.
+    pub pending: HashSet<IPCAddress>,
+    /// Chain epoch beyond which the execution may be swept (rolled
+    /// back) by anyone; `None` if no participant has pre-committed or
+    /// revoked yet.
+    pub timeout_epoch: Option<ChainEpoch>,
+    /// Whether the execution has already been aborted by a `Revoke`
+    /// from some participant. A caller observing this should neither
+    /// resubmit a pre-commit (it would be rejected) nor wait for an
+    /// outcome message that will never come.
+    pub aborted: bool,
+}
+impl Cbor for StatusReturn {}